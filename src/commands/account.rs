@@ -3,16 +3,29 @@
 
 use crate::Result;
 use clap::{Parser, Subcommand};
+use crypto::{
+    hashes::{blake2b::Blake2b256, Digest},
+    signatures::ed25519::{PublicKey, Signature},
+};
 use iota_wallet::{
     account::{
-        types::{AccountAddress, Transaction},
-        AccountHandle,
+        types::{AccountAddress, InclusionState, Transaction},
+        AccountHandle, SyncOptions,
+    },
+    account_manager::AccountManager,
+    iota_client::{
+        bee_message::{
+            output::TokenId,
+            payload::transaction::{Input, TransactionEssence as TransactionPayloadEssence, TransactionId},
+        },
+        mqtt::{MqttEvent, Topic},
+        request_funds_from_faucet,
     },
-    iota_client::{bee_message::output::TokenId, request_funds_from_faucet},
     AddressAndAmount, AddressNativeTokens, U256,
 };
+use serde::{Deserialize, Serialize};
 
-use std::str::FromStr;
+use std::{fs, path::Path, str::FromStr};
 
 #[derive(Parser)]
 #[clap(version, long_about = None)]
@@ -20,6 +33,10 @@ use std::str::FromStr;
 pub struct AccountCli {
     #[clap(subcommand)]
     pub command: AccountCommands,
+    /// Print `list-transactions`, `list-addresses` and `balance` output as JSON instead of the
+    /// human-readable format.
+    #[clap(long, global = true)]
+    pub json: bool,
 }
 
 #[derive(Subcommand)]
@@ -36,7 +53,15 @@ pub enum AccountCommands {
     ListTransactions,
     /// Send an amount to a bech32 address: `send atoi1qzt0nhsf38nh6rs4p6zs5knqp6psgha9wsv74uajqgjmwc75ugupx3y7x0r
     /// 1000000`
-    Send { address: String, amount: u64 },
+    Send {
+        address: String,
+        amount: u64,
+        /// If set, write a JSON payment proof for `address`/`amount` to
+        /// `payment-proof-<transaction id>.json` in the working directory after the transaction
+        /// is created.
+        #[clap(long)]
+        proof: bool,
+    },
     /// Send a native token to a bech32 address: `send-native
     /// atoi1qzt0nhsf38nh6rs4p6zs5knqp6psgha9wsv74uajqgjmwc75ugupx3y7x0r
     /// 08e3a2f76cc934bc0cc21575b4610c1d7d4eb589ae0100000000000000000000000000000000 10`
@@ -46,26 +71,101 @@ pub enum AccountCommands {
         native_token_amount: String,
     },
     /// Sync the account with the Tangle.
-    Sync,
+    Sync {
+        /// Address index to start the address scan from.
+        #[clap(long)]
+        address_start_index: Option<u32>,
+        /// Sync even if the account was already synced recently.
+        #[clap(long)]
+        force: bool,
+        /// Also sync incoming transactions that aren't associated with an output in the account.
+        #[clap(long)]
+        sync_incoming_transactions: bool,
+        /// Also sync pending transactions to check if they got confirmed or conflicting.
+        #[clap(long)]
+        sync_pending_transactions: bool,
+        /// Also sync native token foundries to get their metadata.
+        #[clap(long)]
+        sync_native_token_foundries: bool,
+    },
+    /// Verify a payment proof created by `send --proof`.
+    VerifyProof { path: String },
+    /// Create a backup of the whole account store (stronghold snapshot and database).
+    Backup { path: String, password: String },
+    /// Restore a backup created with `backup`.
+    Restore { path: String, password: String },
+    /// Migrate a Chrysalis `.stronghold` snapshot to a Stardust encrypted database.
+    MigrateStronghold {
+        snapshot_path: String,
+        password: String,
+        new_db_path: String,
+        #[clap(long)]
+        db_encryption_key: String,
+    },
+    /// Recover accounts and addresses from the mnemonic by scanning the Tangle for funds.
+    Recover {
+        /// Number of consecutive empty accounts before recovery stops.
+        #[clap(long, default_value_t = 10)]
+        account_gap_limit: usize,
+        /// Number of consecutive empty addresses per account before moving to the next account.
+        #[clap(long, default_value_t = 20)]
+        address_gap_limit: usize,
+    },
+    /// Watch the account's addresses for incoming and confirmed transactions via MQTT.
+    Watch,
     /// Exit from the account prompt.
     Exit,
 }
 
+/// A receipt proving that `amount` was sent to `recipient` in `tx_id`, signed with the key of
+/// `sender_address`. The public key is not stored in the proof, so checking it needs the account
+/// that owns `sender_address`.
+#[derive(Serialize, Deserialize)]
+struct PaymentProof {
+    recipient: String,
+    amount: u64,
+    tx_id: String,
+    sender_address: String,
+    /// Hex encoded Ed25519 signature over the BLAKE2b-256 hash of the proof message.
+    signature: String,
+}
+
+impl PaymentProof {
+    /// The canonical message that gets signed: UTF-8 bytes of the bech32 recipient address ‖
+    /// amount (big-endian u64) ‖ UTF-8 bytes of the transaction id string.
+    fn message(recipient: &str, amount: u64, tx_id: &str) -> Vec<u8> {
+        let mut message = recipient.as_bytes().to_vec();
+        message.extend_from_slice(&amount.to_be_bytes());
+        message.extend_from_slice(tx_id.as_bytes());
+        message
+    }
+
+    fn hash(recipient: &str, amount: u64, tx_id: &str) -> [u8; 32] {
+        Blake2b256::digest(&Self::message(recipient, amount, tx_id)).into()
+    }
+}
+
 /// `list-transactions` command
-pub async fn list_transactions_command(account_handle: &AccountHandle) -> Result<()> {
+pub async fn list_transactions_command(account_handle: &AccountHandle, json: bool) -> Result<()> {
     let transactions = account_handle.list_transactions().await?;
-    if transactions.is_empty() {
+    if json {
+        println!("{}", serde_json::to_string(&transactions)?);
+    } else if transactions.is_empty() {
         println!("No transactions found");
     } else {
-        transactions.iter().for_each(print_transaction);
+        for transaction in &transactions {
+            print_transaction(account_handle, transaction).await;
+        }
     }
     Ok(())
 }
 
 /// `list-addresses` command
-pub async fn list_addresses_command(account_handle: &AccountHandle) -> Result<()> {
+pub async fn list_addresses_command(account_handle: &AccountHandle, json: bool) -> Result<()> {
     let addresses = account_handle.list_addresses().await.unwrap();
-    if addresses.is_empty() {
+    if json {
+        println!("{}", serde_json::to_string(&addresses)?);
+    } else if addresses.is_empty() {
         println!("No addresses found");
     } else {
         for address in addresses {
@@ -76,9 +176,31 @@ pub async fn list_addresses_command(account_handle: &AccountHandle) -> Result<()
 }
 
 // `sync` command
-pub async fn sync_account_command(account_handle: &AccountHandle) -> Result<()> {
-    let sync = account_handle.sync(None).await?;
-    println!("Synced: {:?}", sync);
+#[allow(clippy::too_many_arguments)]
+pub async fn sync_account_command(
+    account_handle: &AccountHandle,
+    address_start_index: Option<u32>,
+    force: bool,
+    sync_incoming_transactions: bool,
+    sync_pending_transactions: bool,
+    sync_native_token_foundries: bool,
+) -> Result<()> {
+    let mut options = SyncOptions {
+        force_syncing: force,
+        sync_incoming_transactions,
+        sync_pending_transactions,
+        sync_native_token_foundries,
+        ..Default::default()
+    };
+    if let Some(address_start_index) = address_start_index {
+        options.address_start_index = address_start_index;
+    }
+
+    let balance = account_handle.sync(Some(options)).await?;
+    println!(
+        "Synced, new total balance: {} (available: {})",
+        balance.base_coin.total, balance.base_coin.available
+    );
     Ok(())
 }
 
@@ -90,19 +212,103 @@ pub async fn generate_address_command(account_handle: &AccountHandle) -> Result<
 }
 
 // `balance` command
-pub async fn balance_command(account_handle: &AccountHandle) -> Result<()> {
-    println!("{:?}", account_handle.balance().await?);
+pub async fn balance_command(account_handle: &AccountHandle, json: bool) -> Result<()> {
+    let balance = account_handle.balance().await?;
+    if json {
+        println!("{}", serde_json::to_string(&balance)?);
+    } else {
+        println!("{:?}", balance);
+    }
     Ok(())
 }
 
 // `send` command
-pub async fn send_command(account_handle: &AccountHandle, address: String, amount: u64) -> Result<()> {
-    let outputs = vec![AddressAndAmount { address, amount }];
+pub async fn send_command(
+    account_handle: &AccountHandle,
+    address: String,
+    amount: u64,
+    proof: bool,
+) -> Result<()> {
+    let outputs = vec![AddressAndAmount {
+        address: address.clone(),
+        amount,
+    }];
     let transfer_result = account_handle.send_amount(outputs, None).await?;
     println!("Transaction created: {:?}", transfer_result);
+
+    if proof {
+        write_payment_proof(account_handle, &address, amount, &transfer_result.transaction_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Builds and writes a `PaymentProof` for a transaction that has just been created. The proof is
+/// always built from the `recipient`/`amount` that were actually sent (never a separately supplied
+/// value) so it stays binding to the real transfer, and is signed with the private key of
+/// the account address that funded the transaction's first input.
+async fn write_payment_proof(
+    account_handle: &AccountHandle,
+    recipient: &str,
+    amount: u64,
+    tx_id: &TransactionId,
+) -> Result<()> {
+    let tx_id_string = tx_id.to_string();
+    let sending_address = funding_address(account_handle, tx_id).await?;
+
+    let hash = PaymentProof::hash(recipient, amount, &tx_id_string);
+    let signer = account_handle.get_signer().await;
+    let signature = signer
+        .sign_message(&hash, sending_address.key_index(), sending_address.internal())
+        .await?;
+
+    let proof = PaymentProof {
+        recipient: recipient.to_string(),
+        amount,
+        tx_id: tx_id_string.clone(),
+        sender_address: sending_address.address().to_bech32(),
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    let path = format!("payment-proof-{}.json", tx_id_string);
+    fs::write(&path, serde_json::to_string_pretty(&proof)?)?;
+    println!("Payment proof written to {}", path);
     Ok(())
 }
 
+/// Resolves the account address that funded `tx_id`'s first input, so a payment proof is signed
+/// with the key that actually authorized the spend rather than an arbitrary account address.
+async fn funding_address(account_handle: &AccountHandle, tx_id: &TransactionId) -> Result<AccountAddress> {
+    let transactions = account_handle.list_transactions().await?;
+    let transaction = transactions
+        .iter()
+        .find(|tx| &tx.transaction_id == tx_id)
+        .ok_or_else(|| anyhow::anyhow!("Transaction {tx_id} not found in the account"))?;
+
+    let essence = match transaction.payload.essence() {
+        TransactionPayloadEssence::Regular(essence) => essence,
+        _ => return Err(anyhow::anyhow!("Transaction {tx_id} does not have a regular transaction essence")),
+    };
+    let funding_output_id = match essence.inputs().first() {
+        Some(Input::Utxo(input)) => input.output_id(),
+        _ => return Err(anyhow::anyhow!("Transaction {tx_id} has no UTXO inputs to attribute a sender to")),
+    };
+
+    let funding_output = account_handle
+        .list_outputs()
+        .await?
+        .into_iter()
+        .find(|output_data| &output_data.output_id == funding_output_id)
+        .ok_or_else(|| anyhow::anyhow!("Could not resolve the output that funded {tx_id}"))?;
+
+    account_handle
+        .list_addresses()
+        .await?
+        .into_iter()
+        .find(|a| a.address() == &funding_output.address)
+        .ok_or_else(|| anyhow::anyhow!("Funding address for {tx_id} is not one of this account's addresses"))
+}
+
 // `send-native` command
 pub async fn send_native_command(
     account_handle: &AccountHandle,
@@ -137,6 +343,189 @@ pub async fn faucet_command(account_handle: &AccountHandle, url: Option<String>)
     Ok(())
 }
 
+// `verify-proof` command
+pub async fn verify_proof_command(account_handle: &AccountHandle, path: String) -> Result<()> {
+    let proof: PaymentProof = serde_json::from_str(&fs::read_to_string(Path::new(&path))?)?;
+
+    let addresses = account_handle.list_addresses().await?;
+    let sending_address = addresses
+        .iter()
+        .find(|a| a.address().to_bech32() == proof.sender_address)
+        .ok_or_else(|| anyhow::anyhow!("Unknown sender address {}", proof.sender_address))?;
+
+    let signer = account_handle.get_signer().await;
+    let public_key: PublicKey = signer
+        .get_public_key(sending_address.key_index(), sending_address.internal())
+        .await?;
+    let signature = Signature::from_bytes(hex::decode(&proof.signature)?.try_into().map_err(|_| {
+        anyhow::anyhow!("Invalid signature length")
+    })?);
+
+    let hash = PaymentProof::hash(&proof.recipient, proof.amount, &proof.tx_id);
+    if !public_key.verify(&signature, &hash) {
+        return Err(anyhow::anyhow!("Signature verification failed"));
+    }
+
+    // Make sure the transaction the proof is about is actually confirmed on the Tangle, not just
+    // known locally (it could still be pending or have been conflicted out).
+    account_handle.sync(None).await?;
+    let transactions = account_handle.list_transactions().await?;
+    match transactions.iter().find(|tx| tx.transaction_id.to_string() == proof.tx_id) {
+        Some(tx) if tx.inclusion_state == InclusionState::Confirmed => {}
+        Some(tx) => {
+            return Err(anyhow::anyhow!(
+                "Transaction {} is not confirmed yet (inclusion state: {:?})",
+                proof.tx_id,
+                tx.inclusion_state
+            ))
+        }
+        None => return Err(anyhow::anyhow!("Transaction {} not found on the Tangle", proof.tx_id)),
+    }
+
+    println!(
+        "Proof valid: {} received {} from {}",
+        proof.recipient, proof.amount, proof.sender_address
+    );
+    Ok(())
+}
+
+// `backup` command
+pub async fn backup_command(account_manager: &AccountManager, path: String, password: String) -> Result<()> {
+    account_manager.backup(Path::new(&path), password).await?;
+    println!("Backed up the account store to {}", path);
+    Ok(())
+}
+
+// `restore` command
+pub async fn restore_command(account_manager: &AccountManager, path: String, password: String) -> Result<()> {
+    account_manager.restore_backup(Path::new(&path), password).await?;
+    println!("Restored the account store from {}", path);
+    Ok(())
+}
+
+// `migrate-stronghold` command
+pub async fn migrate_stronghold_command(
+    snapshot_path: String,
+    password: String,
+    new_db_path: String,
+    db_encryption_key: String,
+) -> Result<()> {
+    let migrated_addresses = iota_wallet::migration::migrate_db_chrysalis_to_stardust(
+        Path::new(&snapshot_path),
+        password,
+        Path::new(&new_db_path),
+        db_encryption_key.as_bytes(),
+    )
+    .await?;
+
+    // The migrated addresses aren't part of an account's synced store yet, so we can't resolve a
+    // balance/unspent-output list for them the way `print_address` does; print the rest of the
+    // fields through the same helper so the two stay in sync.
+    migrated_addresses.iter().for_each(print_address_basic);
+    println!(
+        "Migrated {} addresses from {} to {}",
+        migrated_addresses.len(),
+        snapshot_path,
+        new_db_path
+    );
+    Ok(())
+}
+
+// `recover` command
+pub async fn recover_command(
+    account_manager: &AccountManager,
+    account_gap_limit: usize,
+    address_gap_limit: usize,
+) -> Result<()> {
+    // `recover_accounts` takes `(account_start_index, account_gap_limit, address_gap_limit,
+    // sync_options)` in that order - keep `account_gap_limit`/`address_gap_limit` passed through
+    // unchanged from our own, identically named parameters above.
+    let recovered_accounts = account_manager
+        .recover_accounts(0, account_gap_limit, address_gap_limit, None)
+        .await?;
+
+    if recovered_accounts.is_empty() {
+        println!("No funds found, nothing to recover");
+        return Ok(());
+    }
+
+    for account_handle in &recovered_accounts {
+        let addresses = account_handle.list_addresses_with_unspent_outputs().await?;
+        for address in addresses {
+            print_address(account_handle, &address).await;
+        }
+    }
+    println!("Recovered {} account(s) with funds", recovered_accounts.len());
+    Ok(())
+}
+
+// `watch` command
+pub async fn watch_command(account_handle: &AccountHandle) -> Result<()> {
+    let addresses = account_handle.list_addresses().await?;
+    let mut topics = Vec::new();
+    for address in &addresses {
+        let bech32 = address.address().to_bech32();
+        topics.push(Topic::new(format!("addresses/{}/outputs", bech32))?);
+    }
+    // Inclusion topics are keyed by transaction id, so watch the transactions that are still pending.
+    for transaction in account_handle.list_transactions().await? {
+        if transaction.inclusion_state == InclusionState::Pending {
+            topics.push(Topic::new(format!(
+                "transactions/{}/included-message",
+                transaction.transaction_id
+            ))?);
+        }
+    }
+
+    println!("Watching {} address(es), press Ctrl+C to stop", addresses.len());
+
+    loop {
+        let account_handle_for_callback = account_handle.clone();
+        let client = account_handle.client().await;
+        let mut mqtt_events = client.mqtt_event_receiver();
+
+        if let Err(e) = client
+            .subscriber()
+            .with_topics(topics.clone())
+            .subscribe(move |event| {
+                println!("New event: {:?}", event.topic);
+                let account_handle = account_handle_for_callback.clone();
+                tokio::spawn(async move {
+                    match account_handle.sync(None).await {
+                        Ok(sync) => println!("Synced: {:?}", sync),
+                        Err(e) => println!("Error syncing after event: {e}"),
+                    }
+                    if let Ok(transactions) = account_handle.list_transactions().await {
+                        if let Some(transaction) = transactions.last() {
+                            print_transaction(&account_handle, transaction).await;
+                        }
+                    }
+                });
+            })
+            .await
+        {
+            println!("MQTT connection failed ({e}), reconnecting...");
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            continue;
+        }
+
+        // Stay on this subscription until either the user interrupts us or the connection drops,
+        // in which case we loop back around and resubscribe instead of silently going quiet.
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            result = mqtt_events.wait_for(|event| *event == MqttEvent::Disconnected) => {
+                if result.is_ok() {
+                    println!("MQTT connection dropped, reconnecting...");
+                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                }
+                continue;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 // `set-alias` command
 // pub async fn set_alias_command(account_handle: &AccountHandle) -> Result<()> {
 //     if let Some(matches) = matches.subcommand_matches("set-alias") {
@@ -146,27 +535,56 @@ pub async fn faucet_command(account_handle: &AccountHandle, url: Option<String>)
 //     Ok(())
 // }
 
-fn print_transaction(transaction: &Transaction) {
-    println!("TRANSACTION {:?}", transaction);
-    // if let Some(MessagePayload::Transaction(tx)) = message.payload() {
-    //     let TransactionEssence::Regular(essence) = tx.essence();
-    //     println!("--- Value: {:?}", essence.value());
-    // }
-    // println!("--- Timestamp: {:?}", message.timestamp());
-    // println!(
-    //     "--- Broadcasted: {}, confirmed: {}",
-    //     message.broadcasted(),
-    //     match message.confirmed() {
-    //         Some(c) => c.to_string(),
-    //         None => "unknown".to_string(),
-    //     }
-    // );
-}
-
-pub async fn print_address(_account_handle: &AccountHandle, address: &AccountAddress) {
-    println!("ADDRESS {:?}", address.address().to_bech32());
-    // println!("Address balance: {}", address.balance());
+async fn print_transaction(account_handle: &AccountHandle, transaction: &Transaction) {
+    println!("TRANSACTION {}", transaction.transaction_id);
+    if let TransactionPayloadEssence::Regular(essence) = transaction.payload.essence() {
+        let own_addresses: Vec<String> = account_handle
+            .list_addresses()
+            .await
+            .map(|addresses| addresses.iter().map(|a| a.address().to_bech32()).collect())
+            .unwrap_or_default();
+
+        // For an incoming transaction the outputs that matter are the ones paying into this
+        // account; for an outgoing one, change/remainder outputs back to this account don't count
+        // as value sent. Either way, keep only the outputs on the side of the transaction implied
+        // by `incoming` and drop the rest (our own change for a send, the sender's change for a
+        // receive).
+        let relevant_outputs: Vec<(String, u64)> = essence
+            .outputs()
+            .iter()
+            .filter_map(|output| {
+                output
+                    .unlock_conditions()
+                    .address()
+                    .map(|unlock_condition| (unlock_condition.address().to_bech32(), output.amount()))
+            })
+            .filter(|(bech32, _)| own_addresses.contains(bech32) == transaction.incoming)
+            .collect();
+
+        let total_value: u64 = relevant_outputs.iter().map(|(_, amount)| amount).sum();
+        let recipients: Vec<&String> = relevant_outputs.iter().map(|(bech32, _)| bech32).collect();
+
+        println!("--- Value: {}", total_value);
+        println!("--- Recipients: {:?}", recipients);
+    }
+    println!("--- Timestamp: {}", transaction.timestamp);
+    println!("--- Inclusion state: {:?}", transaction.inclusion_state);
+}
+
+/// Prints the parts of an `AccountAddress` that don't require an `AccountHandle` to resolve (no
+/// balance/unspent-output lookup), so addresses that aren't in an account's store yet (e.g. freshly
+/// migrated ones) can still be displayed consistently with `print_address`.
+fn print_address_basic(address: &AccountAddress) {
+    println!("ADDRESS {}", address.address().to_bech32());
     println!("--- Index: {}", address.key_index());
     println!("--- Change address: {}", address.internal());
-    // println!("--- Address outputs: {}", address.output_ids());
+}
+
+pub async fn print_address(account_handle: &AccountHandle, address: &AccountAddress) {
+    print_address_basic(address);
+    println!("--- Balance: {}", account_handle.address_balance(address).await);
+    println!(
+        "--- Unspent outputs: {:?}",
+        account_handle.address_unspent_output_ids(address).await
+    );
 }
\ No newline at end of file